@@ -1,331 +1,919 @@
-use bevy::{ecs::query::BatchingStrategy, prelude::*};
-use kd_tree::{KdPoint, KdTree};
-
-#[derive(Clone, Debug)]
-pub struct KdTreeItem {
-    point: [f32; 2],
-    entity: Entity,
-}
-
-impl KdPoint for KdTreeItem {
-    type Scalar = f32;
-    type Dim = typenum::U2; // 2 dimensional tree.
-    fn at(&self, k: usize) -> f32 {
-        self.point[k]
-    }
-}
-
-#[derive(Resource)]
-pub struct SpatialTree {
-    pub tree: KdTree<KdTreeItem>,
-}
-
-impl SpatialTree {
-    pub fn query_within_radius(&self, point: &[f32; 2], radius: f32) -> Vec<&KdTreeItem> {
-        //let trace_span = info_span!("query_within_radius", name = "query_within_radius");
-        //let _span_guard = trace_span.enter();
-
-        self.tree.within_radius(point, radius)
-    }
-}
-
-#[derive(Resource)]
-pub struct ForceMultipliers {
-    separation: f32,
-    alignment: f32,
-    cohesion: f32,
-}
-
-#[derive(Component)]
-pub struct Velocity(pub Vec3);
-
-#[derive(Component)]
-pub struct SeparationForce(pub Vec3);
-
-#[derive(Component)]
-pub struct AligmentForce(pub Vec3);
-
-#[derive(Component)]
-pub struct CohesionForce(pub Vec3);
-
-#[derive(Component)]
-pub struct Acceleration(pub Vec3);
-
-#[derive(Component)]
-pub struct MaxSpeed(pub f32);
-
-#[derive(Component)]
-pub struct MaxForce(pub f32);
-
-#[derive(Component)]
-pub struct TrackedByKdTree;
-
-#[derive(Bundle)]
-pub struct BoidBundle {
-    pub velocity: Velocity,
-    pub aligment_force: AligmentForce,
-    pub separation_force: SeparationForce,
-    pub cohesion_force: CohesionForce,
-    pub acceleration: Acceleration,
-    pub max_speed: MaxSpeed,
-    pub max_force: MaxForce,
-    pub tracked_by_kd_tree: TrackedByKdTree,
-}
-
-impl Default for BoidBundle {
-    fn default() -> Self {
-        Self {
-            velocity: Velocity(Vec3::new(0., 0., 0.)),
-            aligment_force: AligmentForce(Vec3::new(0., 0., 0.)),
-            separation_force: SeparationForce(Vec3::new(0., 0., 0.)),
-            cohesion_force: CohesionForce(Vec3::new(0., 0., 0.)),
-            acceleration: Acceleration(Vec3::new(0., 0., 0.)),
-            max_speed: MaxSpeed(4. * 60.),
-            max_force: MaxForce(0.5 * 60.),
-            tracked_by_kd_tree: TrackedByKdTree,
-        }
-    }
-}
-
-pub struct BoidsPlugin;
-
-impl Plugin for BoidsPlugin {
-    fn build(&self, app: &mut App) {
-        app.insert_resource(ForceMultipliers {
-            separation: 2.0,
-            alignment: 1.0,
-            cohesion: 1.0,
-        })
-        .insert_resource(SpatialTree {
-            tree: kd_tree::KdTree::build_by_ordered_float(Vec::new()),
-        })
-        .add_systems(PreUpdate, (wrap_around_screen, update_spatial_tree))
-        .add_systems(
-            Update,
-            (
-                separate,
-                align_and_cohesion,
-                //cohesion,
-                apply_acceleration,
-                update_position,
-            ),
-        );
-    }
-}
-
-fn update_spatial_tree(
-    query: Query<(Entity, &Transform), With<TrackedByKdTree>>,
-    mut kd_tree: ResMut<SpatialTree>,
-) {
-    let mut raw_vec = Vec::with_capacity(query.iter().len());
-
-    for (entity, transform) in query.iter() {
-        raw_vec.push(KdTreeItem {
-            point: [transform.translation.x, transform.translation.y],
-            entity,
-        });
-    }
-
-    kd_tree.tree = kd_tree::KdTree::par_build_by_ordered_float(raw_vec);
-}
-
-fn wrap_around_screen(
-    mut query: Query<(&mut Transform, &Velocity)>,
-    camera_query: Query<(&Camera, &GlobalTransform)>,
-) {
-    let (camera, _) = camera_query.single();
-
-    query.par_iter_mut().for_each(|(mut transform, _)| {
-        let viewport_size = camera.logical_target_size().unwrap();
-
-        if transform.translation.x > viewport_size.x / 2. {
-            transform.translation.x = -viewport_size.x / 2.;
-        } else if transform.translation.x < -viewport_size.x / 2. {
-            transform.translation.x = viewport_size.x / 2.;
-        }
-
-        if transform.translation.y > viewport_size.y / 2. {
-            transform.translation.y = -viewport_size.y / 2.;
-        } else if transform.translation.y < -viewport_size.y / 2. {
-            transform.translation.y = viewport_size.y / 2.;
-        }
-    });
-}
-
-fn separate(
-    mut query: Query<(
-        &Transform,
-        &mut SeparationForce,
-        &Velocity,
-        &MaxSpeed,
-        &MaxForce,
-        With<TrackedByKdTree>,
-    )>,
-    force_multipliers: Res<ForceMultipliers>,
-    kd_tree: Res<SpatialTree>,
-) {
-    let desired_separation = 10.;
-
-    query
-        .par_iter_mut()
-        .batching_strategy(BatchingStrategy::fixed(100))
-        .for_each(
-            |(transform, mut separation_force, velocity, max_speed, max_force, ())| {
-                let mut sum = Vec3::new(0., 0., 0.);
-                let mut count = 0;
-                let location = Vec2::new(transform.translation.x, transform.translation.y);
-
-                let results =
-                    kd_tree.query_within_radius(&[location.x, location.y], desired_separation);
-
-                for result in &results {
-                    let other_position = Vec3::new(result.point[0], result.point[1], 0.);
-                    let distance = transform.translation.distance(other_position);
-
-                    if distance == 0.0 {
-                        continue;
-                    }
-
-                    let mut diff = transform.translation - other_position;
-                    diff = diff.normalize_or_zero() / distance;
-                    sum += diff;
-                    count += 1;
-                }
-
-                if count > 0 {
-                    sum /= count as f32;
-                    sum = sum.normalize_or_zero();
-                    sum *= max_speed.0;
-                    let mut steer = sum - velocity.0;
-
-                    steer = clamp_magnitude(steer, max_force.0);
-                    separation_force.0 += steer * force_multipliers.separation;
-                }
-            },
-        );
-}
-
-fn align_and_cohesion(
-    mut query: Query<(
-        &Transform,
-        &mut AligmentForce,
-        &mut CohesionForce,
-        &Velocity,
-        &MaxSpeed,
-        &MaxForce,
-        With<TrackedByKdTree>,
-    )>,
-    other_query: Query<(&Transform, &Velocity), With<TrackedByKdTree>>,
-    force_multipliers: Res<ForceMultipliers>,
-    kd_tree: Res<SpatialTree>,
-) {
-    let neighbor_distance = 20.;
-
-    query
-        .par_iter_mut()
-        .batching_strategy(BatchingStrategy::fixed(100))
-        .for_each(
-            |(
-                transform,
-                mut aligment_force,
-                mut cohesion_force,
-                velocity,
-                max_speed,
-                max_force,
-                (),
-            )| {
-                let mut position_sum = Vec3::new(0., 0., 0.);
-                let mut velocity_sum = Vec3::new(0., 0., 0.);
-                let mut count = 0;
-                let location = Vec2::new(transform.translation.x, transform.translation.y);
-
-                let results =
-                    kd_tree.query_within_radius(&[location.x, location.y], neighbor_distance);
-
-                for result in &results {
-                    let other_position = Vec3::new(result.point[0], result.point[1], 0.);
-                    position_sum += other_position;
-
-                    if let Ok((_, velocity)) = other_query.get(result.entity) {
-                        velocity_sum += velocity.0;
-                    }
-                    count += 1;
-                }
-
-                if count == 0 {
-                    return;
-                }
-
-                // Compute alignment
-                velocity_sum /= count as f32;
-                velocity_sum = velocity_sum.normalize_or_zero();
-                velocity_sum *= max_speed.0;
-
-                let mut velocity_diff = velocity_sum - velocity.0;
-
-                velocity_diff = clamp_magnitude(velocity_diff, max_force.0);
-                aligment_force.0 += velocity_diff * force_multipliers.alignment;
-
-                // Compute cohesion
-                position_sum /= count as f32;
-
-                let mut desired_position = position_sum - transform.translation;
-                desired_position = desired_position.normalize_or_zero();
-                desired_position *= max_speed.0;
-
-                let mut steer = desired_position - velocity.0;
-                steer = clamp_magnitude(steer, max_force.0);
-                cohesion_force.0 += steer * force_multipliers.cohesion;
-            },
-        );
-}
-
-fn apply_acceleration(
-    mut query: Query<(
-        &mut Velocity,
-        &mut Acceleration,
-        &mut AligmentForce,
-        &mut SeparationForce,
-        &mut CohesionForce,
-        &MaxSpeed,
-    )>,
-    time: Res<Time>,
-) {
-    query.par_iter_mut().for_each(
-        |(
-            mut velocity,
-            mut acceleration,
-            mut alignment,
-            mut separation,
-            mut cohesion,
-            max_speed,
-        )| {
-            acceleration.0 += alignment.0;
-            acceleration.0 += separation.0;
-            acceleration.0 += cohesion.0;
-
-            velocity.0 += acceleration.0 * time.delta_seconds();
-            velocity.0 = clamp_magnitude(velocity.0, max_speed.0);
-
-            acceleration.0 *= 0.;
-            alignment.0 *= 0.;
-            separation.0 *= 0.;
-            cohesion.0 *= 0.;
-        },
-    );
-}
-
-fn update_position(mut query: Query<(&mut Transform, &Velocity)>, time: Res<Time>) {
-    query.par_iter_mut().for_each(|(mut transform, velocity)| {
-        transform.translation += velocity.0 * time.delta_seconds();
-        transform.rotation = Quat::from_rotation_z(velocity.0.y.atan2(velocity.0.x) + 180.);
-    });
-}
-
-pub fn clamp_magnitude(value: Vec3, max: f32) -> Vec3 {
-    if value.length() > max {
-        value.normalize_or_zero() * max
-    } else {
-        value
-    }
-}
+use std::collections::HashMap;
+
+use bevy::{ecs::query::BatchingStrategy, prelude::*, time::Fixed};
+use kd_tree::{KdPoint, KdTree};
+use parry2d::{
+    math::Isometry,
+    query::{self, ClosestPoints},
+    shape::Ball,
+};
+use serde::{Deserialize, Serialize};
+
+// The tree always stores 3 coordinates so the same item works for both the
+// `TwoD` and `ThreeD` BoidsDimension: 2D boids simply keep `point[2]` at 0,
+// which makes neighbor distances agree with the old 2D-only behavior.
+#[derive(Clone, Debug)]
+pub struct KdTreeItem {
+    point: [f32; 3],
+    entity: Entity,
+}
+
+impl KdPoint for KdTreeItem {
+    type Scalar = f32;
+    type Dim = typenum::U3;
+    fn at(&self, k: usize) -> f32 {
+        self.point[k]
+    }
+}
+
+/// A neighbor-query backend boid-to-boid force systems query through
+/// `SpatialTree`. Lets `BoidsPlugin` trade `KdTreeIndex`'s sorted rebuild
+/// for `HashGridIndex`'s cheaper O(n) per-frame rebuild on dense, uniform
+/// flocks; both return exactly the items within the query radius.
+pub trait SpatialIndex: Send + Sync {
+    fn update(&mut self, items: Vec<KdTreeItem>);
+    fn query_within_radius(&self, point: &[f32; 3], radius: f32) -> Vec<&KdTreeItem>;
+
+    /// Called once per `update_spatial_tree` tick with the largest radius any
+    /// force system will query this frame, so bucketed backends
+    /// (`HashGridIndex`) can keep their cell width from going stale once
+    /// `FlockingParams` is tuned live. `KdTreeIndex` has no fixed bucketing
+    /// and ignores it.
+    fn set_max_query_radius(&mut self, _radius: f32) {}
+}
+
+pub struct KdTreeIndex {
+    tree: KdTree<KdTreeItem>,
+}
+
+impl Default for KdTreeIndex {
+    fn default() -> Self {
+        Self {
+            tree: KdTree::build_by_ordered_float(Vec::new()),
+        }
+    }
+}
+
+impl SpatialIndex for KdTreeIndex {
+    fn update(&mut self, items: Vec<KdTreeItem>) {
+        self.tree = KdTree::par_build_by_ordered_float(items);
+    }
+
+    fn query_within_radius(&self, point: &[f32; 3], radius: f32) -> Vec<&KdTreeItem> {
+        self.tree.within_radius(point, radius)
+    }
+}
+
+/// Buckets items into cells sized to the largest query radius, keyed by
+/// integer cell coordinates, so rebuilds are O(n) insertion with no sorting
+/// and radius queries only scan the cell neighborhood instead of the tree.
+pub struct HashGridIndex {
+    cell_size: f32,
+    dimension: BoidsDimension,
+    cells: HashMap<(i32, i32, i32), Vec<KdTreeItem>>,
+}
+
+impl HashGridIndex {
+    pub fn new(cell_size: f32, dimension: BoidsDimension) -> Self {
+        Self {
+            cell_size,
+            dimension,
+            cells: HashMap::new(),
+        }
+    }
+
+    fn cell_coord(&self, point: &[f32; 3]) -> (i32, i32, i32) {
+        (
+            (point[0] / self.cell_size).floor() as i32,
+            (point[1] / self.cell_size).floor() as i32,
+            (point[2] / self.cell_size).floor() as i32,
+        )
+    }
+}
+
+impl SpatialIndex for HashGridIndex {
+    fn update(&mut self, items: Vec<KdTreeItem>) {
+        self.cells.clear();
+
+        for item in items {
+            self.cells
+                .entry(self.cell_coord(&item.point))
+                .or_default()
+                .push(item);
+        }
+    }
+
+    fn query_within_radius(&self, point: &[f32; 3], radius: f32) -> Vec<&KdTreeItem> {
+        let (cx, cy, cz) = self.cell_coord(point);
+        let radius_squared = radius * radius;
+        let z_range = if self.dimension == BoidsDimension::ThreeD {
+            -1..=1
+        } else {
+            0..=0
+        };
+
+        let mut results = Vec::new();
+
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in z_range.clone() {
+                    let Some(items) = self.cells.get(&(cx + dx, cy + dy, cz + dz)) else {
+                        continue;
+                    };
+
+                    results.extend(items.iter().filter(|item| {
+                        let dx = item.point[0] - point[0];
+                        let dy = item.point[1] - point[1];
+                        let dz = item.point[2] - point[2];
+                        dx * dx + dy * dy + dz * dz <= radius_squared
+                    }));
+                }
+            }
+        }
+
+        results
+    }
+
+    fn set_max_query_radius(&mut self, radius: f32) {
+        self.cell_size = radius.max(f32::EPSILON);
+    }
+}
+
+/// Which `SpatialIndex` implementation `BoidsPlugin` builds.
+#[derive(Clone, Copy, Default)]
+pub enum SpatialIndexBackend {
+    #[default]
+    KdTree,
+    HashGrid,
+}
+
+#[derive(Resource)]
+pub struct SpatialTree {
+    index: Box<dyn SpatialIndex>,
+}
+
+impl SpatialTree {
+    pub fn update(&mut self, items: Vec<KdTreeItem>) {
+        self.index.update(items);
+    }
+
+    pub fn set_max_query_radius(&mut self, radius: f32) {
+        self.index.set_max_query_radius(radius);
+    }
+
+    pub fn query_within_radius(&self, point: &[f32; 3], radius: f32) -> Vec<&KdTreeItem> {
+        //let trace_span = info_span!("query_within_radius", name = "query_within_radius");
+        //let _span_guard = trace_span.enter();
+
+        self.index.query_within_radius(point, radius)
+    }
+}
+
+/// Selects whether boids move, neighbor-query and bound themselves in a
+/// plane or in full 3D world space.
+#[derive(Resource, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BoidsDimension {
+    #[default]
+    TwoD,
+    ThreeD,
+}
+
+/// The AABB `ThreeD` boids are bound inside, in place of screen wrapping.
+#[derive(Resource, Clone, Copy)]
+pub struct BoidsVolume {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Default for BoidsVolume {
+    fn default() -> Self {
+        Self {
+            min: Vec3::new(-500., -500., -500.),
+            max: Vec3::new(500., 500., 500.),
+        }
+    }
+}
+
+#[derive(Resource, Reflect, Serialize, Deserialize, Clone, Copy)]
+#[reflect(Resource)]
+pub struct ForceMultipliers {
+    separation: f32,
+    alignment: f32,
+    cohesion: f32,
+    avoidance: f32,
+    flee: f32,
+}
+
+/// The magic numbers steering and neighbor-query systems used to hardcode
+/// locally, pulled into one resource so they can be edited live in an
+/// inspector panel and saved/loaded as part of a flocking preset.
+#[derive(Resource, Reflect, Serialize, Deserialize, Clone, Copy)]
+#[reflect(Resource)]
+pub struct FlockingParams {
+    pub desired_separation: f32,
+    pub neighbor_distance: f32,
+    pub target_radius: f32,
+    pub max_speed: f32,
+    pub max_force: f32,
+    /// How close a `Predator` has to be before a boid starts fleeing it.
+    pub flee_radius: f32,
+}
+
+impl Default for FlockingParams {
+    fn default() -> Self {
+        Self {
+            desired_separation: 10.,
+            neighbor_distance: 20.,
+            target_radius: 100.,
+            max_speed: 4. * 60.,
+            max_force: 0.5 * 60.,
+            flee_radius: 150.,
+        }
+    }
+}
+
+/// Static collision geometry that boids steer around. `avoid_obstacles` is
+/// built on parry2d and only reads x/y, so obstacles are only avoided in
+/// `BoidsDimension::TwoD`; in `ThreeD` it would treat each one as an infinite
+/// vertical cylinder, so the system is left out of the `FixedUpdate` chain
+/// and obstacles have no effect on boids.
+#[derive(Component, Clone, Copy)]
+pub struct Obstacle {
+    pub center: Vec2,
+    pub radius: f32,
+}
+
+/// Every spawned `Obstacle`, mirrored here for `avoid_obstacles` to query.
+#[derive(Resource, Default)]
+pub struct Obstacles {
+    pub items: Vec<Obstacle>,
+}
+
+/// Marks an entity that boids steer away from once within `flee_radius`,
+/// driven by the cursor in `move_predator_with_cursor`. The cursor only ever
+/// gives the predator an x/y, so `flee` (like `avoid_obstacles`) is only run
+/// in `BoidsDimension::TwoD`.
+#[derive(Component)]
+pub struct Predator;
+
+/// Every `Predator` position, mirrored here for `flee` to query.
+#[derive(Resource, Default)]
+pub struct Predators {
+    pub items: Vec<Vec3>,
+}
+
+#[derive(Component)]
+pub struct Velocity(pub Vec3);
+
+#[derive(Component)]
+pub struct SeparationForce(pub Vec3);
+
+#[derive(Component)]
+pub struct AligmentForce(pub Vec3);
+
+#[derive(Component)]
+pub struct CohesionForce(pub Vec3);
+
+#[derive(Component)]
+pub struct AvoidanceForce(pub Vec3);
+
+#[derive(Component)]
+pub struct FleeForce(pub Vec3);
+
+#[derive(Component)]
+pub struct Acceleration(pub Vec3);
+
+#[derive(Component)]
+pub struct MaxSpeed(pub f32);
+
+#[derive(Component)]
+pub struct MaxForce(pub f32);
+
+#[derive(Component)]
+pub struct TrackedByKdTree;
+
+/// The authoritative position advanced once per `FixedUpdate` step. `Transform`
+/// is left for rendering only, interpolated between this and
+/// `PreviousSimPosition` so boids look smooth regardless of frame rate.
+#[derive(Component, Clone, Copy, Default)]
+pub struct SimPosition(pub Vec3);
+
+#[derive(Component, Clone, Copy, Default)]
+pub struct PreviousSimPosition(pub Vec3);
+
+#[derive(Bundle)]
+pub struct BoidBundle {
+    pub velocity: Velocity,
+    pub aligment_force: AligmentForce,
+    pub separation_force: SeparationForce,
+    pub cohesion_force: CohesionForce,
+    pub avoidance_force: AvoidanceForce,
+    pub flee_force: FleeForce,
+    pub acceleration: Acceleration,
+    pub max_speed: MaxSpeed,
+    pub max_force: MaxForce,
+    pub tracked_by_kd_tree: TrackedByKdTree,
+    pub sim_position: SimPosition,
+    pub previous_sim_position: PreviousSimPosition,
+}
+
+impl Default for BoidBundle {
+    fn default() -> Self {
+        Self::from_params(&FlockingParams::default())
+    }
+}
+
+impl BoidBundle {
+    /// Builds a bundle whose `MaxSpeed`/`MaxForce` are copied from the
+    /// current `FlockingParams`, so spawned boids pick up inspector/preset
+    /// tuning. The copy is made once at spawn: later edits to `max_speed` or
+    /// `max_force` only reach boids spawned afterwards.
+    pub fn from_params(params: &FlockingParams) -> Self {
+        Self {
+            velocity: Velocity(Vec3::new(0., 0., 0.)),
+            aligment_force: AligmentForce(Vec3::new(0., 0., 0.)),
+            separation_force: SeparationForce(Vec3::new(0., 0., 0.)),
+            cohesion_force: CohesionForce(Vec3::new(0., 0., 0.)),
+            avoidance_force: AvoidanceForce(Vec3::new(0., 0., 0.)),
+            flee_force: FleeForce(Vec3::new(0., 0., 0.)),
+            acceleration: Acceleration(Vec3::new(0., 0., 0.)),
+            max_speed: MaxSpeed(params.max_speed),
+            max_force: MaxForce(params.max_force),
+            tracked_by_kd_tree: TrackedByKdTree,
+            sim_position: SimPosition::default(),
+            previous_sim_position: PreviousSimPosition::default(),
+        }
+    }
+}
+
+pub struct BoidsPlugin {
+    pub dimension: BoidsDimension,
+    /// How many times per second force computation and integration run in
+    /// `FixedUpdate`, independent of the render frame rate.
+    pub simulation_hz: f64,
+    /// Which `SpatialIndex` backs boid-to-boid neighbor queries.
+    pub spatial_index_backend: SpatialIndexBackend,
+}
+
+impl Default for BoidsPlugin {
+    fn default() -> Self {
+        Self {
+            dimension: BoidsDimension::default(),
+            simulation_hz: 60.,
+            spatial_index_backend: SpatialIndexBackend::default(),
+        }
+    }
+}
+
+impl Plugin for BoidsPlugin {
+    fn build(&self, app: &mut App) {
+        let flocking_params = FlockingParams::default();
+        let index: Box<dyn SpatialIndex> = match self.spatial_index_backend {
+            SpatialIndexBackend::KdTree => Box::<KdTreeIndex>::default(),
+            SpatialIndexBackend::HashGrid => Box::new(HashGridIndex::new(
+                flocking_params.neighbor_distance,
+                self.dimension,
+            )),
+        };
+
+        app.insert_resource(ForceMultipliers {
+            separation: 2.0,
+            alignment: 1.0,
+            cohesion: 1.0,
+            avoidance: 3.0,
+            flee: 4.0,
+        })
+        .insert_resource(SpatialTree { index })
+        .insert_resource(Obstacles::default())
+        .insert_resource(Predators::default())
+        .insert_resource(self.dimension)
+        .insert_resource(BoidsVolume::default())
+        .insert_resource(Time::<Fixed>::from_hz(self.simulation_hz))
+        .init_resource::<FlockingParams>()
+        .register_type::<ForceMultipliers>()
+        .register_type::<FlockingParams>()
+        .add_systems(Startup, spawn_predator)
+        .add_systems(
+            PreUpdate,
+            (
+                init_sim_position,
+                update_spatial_tree,
+                update_obstacles,
+                update_predators,
+            ),
+        )
+        .add_systems(Update, (interpolate_transform, move_predator_with_cursor));
+
+        // Chained so the scheduler can't reorder this: every tick must snapshot
+        // `PreviousSimPosition`, accumulate forces, apply them, and only then
+        // advance `SimPosition`, or `interpolate_transform` renders garbage.
+        match self.dimension {
+            BoidsDimension::TwoD => {
+                app.add_systems(
+                    FixedUpdate,
+                    (
+                        store_previous_sim_position,
+                        separate,
+                        align_and_cohesion,
+                        //cohesion,
+                        avoid_obstacles,
+                        flee,
+                        apply_acceleration,
+                        update_position,
+                        wrap_around_screen,
+                    )
+                        .chain(),
+                );
+            }
+            BoidsDimension::ThreeD => {
+                // `avoid_obstacles` and `flee` are both built on parry2d/Vec2
+                // and only ever read a boid's x/y, so in 3D they'd treat
+                // every `Obstacle`/`Predator` as an infinite vertical
+                // cylinder. Leave them out until they're generalized to 3D.
+                app.add_systems(
+                    FixedUpdate,
+                    (
+                        store_previous_sim_position,
+                        separate,
+                        align_and_cohesion,
+                        //cohesion,
+                        apply_acceleration,
+                        update_position_3d,
+                        bound_within_volume,
+                    )
+                        .chain(),
+                );
+            }
+        }
+    }
+}
+
+/// Seeds `SimPosition`/`PreviousSimPosition` from the spawn `Transform` the
+/// first time a boid shows up, so it doesn't snap in from the origin before
+/// its first `FixedUpdate` step.
+fn init_sim_position(
+    mut query: Query<
+        (&Transform, &mut SimPosition, &mut PreviousSimPosition),
+        Added<TrackedByKdTree>,
+    >,
+) {
+    for (transform, mut sim_position, mut previous_sim_position) in &mut query {
+        sim_position.0 = transform.translation;
+        previous_sim_position.0 = transform.translation;
+    }
+}
+
+fn store_previous_sim_position(mut query: Query<(&SimPosition, &mut PreviousSimPosition)>) {
+    query
+        .par_iter_mut()
+        .for_each(|(sim_position, mut previous_sim_position)| {
+            previous_sim_position.0 = sim_position.0;
+        });
+}
+
+/// Smooths the rendered `Transform` between the previous and current
+/// fixed-step positions so motion looks continuous even when
+/// `simulation_hz` is lower than the display refresh rate.
+fn interpolate_transform(
+    mut query: Query<(&mut Transform, &SimPosition, &PreviousSimPosition)>,
+    fixed_time: Res<Time<Fixed>>,
+) {
+    let alpha = fixed_time.overstep_fraction();
+
+    query
+        .par_iter_mut()
+        .for_each(|(mut transform, sim_position, previous_sim_position)| {
+            transform.translation = previous_sim_position.0.lerp(sim_position.0, alpha);
+        });
+}
+
+fn update_spatial_tree(
+    query: Query<(Entity, &SimPosition), With<TrackedByKdTree>>,
+    mut kd_tree: ResMut<SpatialTree>,
+    flocking_params: Res<FlockingParams>,
+) {
+    let mut raw_vec = Vec::with_capacity(query.iter().len());
+
+    for (entity, sim_position) in query.iter() {
+        raw_vec.push(KdTreeItem {
+            point: [sim_position.0.x, sim_position.0.y, sim_position.0.z],
+            entity,
+        });
+    }
+
+    kd_tree.set_max_query_radius(
+        flocking_params
+            .desired_separation
+            .max(flocking_params.neighbor_distance),
+    );
+    kd_tree.update(raw_vec);
+}
+
+fn update_obstacles(query: Query<&Obstacle>, mut obstacles: ResMut<Obstacles>) {
+    obstacles.items = query.iter().copied().collect();
+}
+
+fn spawn_predator(mut commands: Commands) {
+    commands.spawn((Predator, TransformBundle::default()));
+}
+
+/// Keeps the `Predator` glued to the cursor.
+fn move_predator_with_cursor(
+    mut predator_query: Query<&mut Transform, With<Predator>>,
+    mut mouse_motion_events: EventReader<CursorMoved>,
+    camera_query: Query<(&Camera, &GlobalTransform)>,
+) {
+    let (camera, camera_transform) = camera_query.single();
+
+    for event in mouse_motion_events.read() {
+        let Some(world_position) = camera.viewport_to_world_2d(camera_transform, event.position)
+        else {
+            continue;
+        };
+
+        for mut transform in &mut predator_query {
+            transform.translation = Vec3::new(world_position.x, world_position.y, 0.);
+        }
+    }
+}
+
+fn update_predators(query: Query<&Transform, With<Predator>>, mut predators: ResMut<Predators>) {
+    predators.items = query
+        .iter()
+        .map(|transform| transform.translation)
+        .collect();
+}
+
+fn wrap_around_screen(
+    mut query: Query<(&mut SimPosition, &Velocity)>,
+    camera_query: Query<(&Camera, &GlobalTransform)>,
+) {
+    let (camera, _) = camera_query.single();
+
+    query.par_iter_mut().for_each(|(mut sim_position, _)| {
+        let viewport_size = camera.logical_target_size().unwrap();
+
+        if sim_position.0.x > viewport_size.x / 2. {
+            sim_position.0.x = -viewport_size.x / 2.;
+        } else if sim_position.0.x < -viewport_size.x / 2. {
+            sim_position.0.x = viewport_size.x / 2.;
+        }
+
+        if sim_position.0.y > viewport_size.y / 2. {
+            sim_position.0.y = -viewport_size.y / 2.;
+        } else if sim_position.0.y < -viewport_size.y / 2. {
+            sim_position.0.y = viewport_size.y / 2.;
+        }
+    });
+}
+
+fn bound_within_volume(
+    mut query: Query<&mut SimPosition, With<Velocity>>,
+    volume: Res<BoidsVolume>,
+) {
+    query.par_iter_mut().for_each(|mut sim_position| {
+        let p = sim_position.0;
+
+        sim_position.0 = Vec3::new(
+            wrap(p.x, volume.min.x, volume.max.x),
+            wrap(p.y, volume.min.y, volume.max.y),
+            wrap(p.z, volume.min.z, volume.max.z),
+        );
+    });
+}
+
+fn wrap(value: f32, min: f32, max: f32) -> f32 {
+    if value > max {
+        min
+    } else if value < min {
+        max
+    } else {
+        value
+    }
+}
+
+fn separate(
+    mut query: Query<(
+        &SimPosition,
+        &mut SeparationForce,
+        &Velocity,
+        &MaxSpeed,
+        &MaxForce,
+        With<TrackedByKdTree>,
+    )>,
+    force_multipliers: Res<ForceMultipliers>,
+    kd_tree: Res<SpatialTree>,
+    flocking_params: Res<FlockingParams>,
+) {
+    let desired_separation = flocking_params.desired_separation;
+
+    query
+        .par_iter_mut()
+        .batching_strategy(BatchingStrategy::fixed(100))
+        .for_each(
+            |(sim_position, mut separation_force, velocity, max_speed, max_force, ())| {
+                let mut sum = Vec3::new(0., 0., 0.);
+                let mut count = 0;
+                let location = sim_position.0;
+
+                let results = kd_tree
+                    .query_within_radius(&[location.x, location.y, location.z], desired_separation);
+
+                for result in &results {
+                    let other_position =
+                        Vec3::new(result.point[0], result.point[1], result.point[2]);
+                    let distance = location.distance(other_position);
+
+                    if distance == 0.0 {
+                        continue;
+                    }
+
+                    let mut diff = location - other_position;
+                    diff = diff.normalize_or_zero() / distance;
+                    sum += diff;
+                    count += 1;
+                }
+
+                if count > 0 {
+                    sum /= count as f32;
+                    sum = sum.normalize_or_zero();
+                    sum *= max_speed.0;
+                    let mut steer = sum - velocity.0;
+
+                    steer = clamp_magnitude(steer, max_force.0);
+                    separation_force.0 += steer * force_multipliers.separation;
+                }
+            },
+        );
+}
+
+fn align_and_cohesion(
+    mut query: Query<(
+        &SimPosition,
+        &mut AligmentForce,
+        &mut CohesionForce,
+        &Velocity,
+        &MaxSpeed,
+        &MaxForce,
+        With<TrackedByKdTree>,
+    )>,
+    other_query: Query<(&SimPosition, &Velocity), With<TrackedByKdTree>>,
+    force_multipliers: Res<ForceMultipliers>,
+    kd_tree: Res<SpatialTree>,
+    flocking_params: Res<FlockingParams>,
+) {
+    let neighbor_distance = flocking_params.neighbor_distance;
+
+    query
+        .par_iter_mut()
+        .batching_strategy(BatchingStrategy::fixed(100))
+        .for_each(
+            |(
+                sim_position,
+                mut aligment_force,
+                mut cohesion_force,
+                velocity,
+                max_speed,
+                max_force,
+                (),
+            )| {
+                let mut position_sum = Vec3::new(0., 0., 0.);
+                let mut velocity_sum = Vec3::new(0., 0., 0.);
+                let mut count = 0;
+                let location = sim_position.0;
+
+                let results = kd_tree
+                    .query_within_radius(&[location.x, location.y, location.z], neighbor_distance);
+
+                for result in &results {
+                    let other_position =
+                        Vec3::new(result.point[0], result.point[1], result.point[2]);
+                    position_sum += other_position;
+
+                    if let Ok((_, velocity)) = other_query.get(result.entity) {
+                        velocity_sum += velocity.0;
+                    }
+                    count += 1;
+                }
+
+                if count == 0 {
+                    return;
+                }
+
+                // Compute alignment
+                velocity_sum /= count as f32;
+                velocity_sum = velocity_sum.normalize_or_zero();
+                velocity_sum *= max_speed.0;
+
+                let mut velocity_diff = velocity_sum - velocity.0;
+
+                velocity_diff = clamp_magnitude(velocity_diff, max_force.0);
+                aligment_force.0 += velocity_diff * force_multipliers.alignment;
+
+                // Compute cohesion
+                position_sum /= count as f32;
+
+                let mut desired_position = position_sum - location;
+                desired_position = desired_position.normalize_or_zero();
+                desired_position *= max_speed.0;
+
+                let mut steer = desired_position - velocity.0;
+                steer = clamp_magnitude(steer, max_force.0);
+                cohesion_force.0 += steer * force_multipliers.cohesion;
+            },
+        );
+}
+
+fn avoid_obstacles(
+    mut query: Query<(
+        &SimPosition,
+        &mut AvoidanceForce,
+        &Velocity,
+        &MaxSpeed,
+        &MaxForce,
+        With<TrackedByKdTree>,
+    )>,
+    obstacles: Res<Obstacles>,
+    force_multipliers: Res<ForceMultipliers>,
+) {
+    let lookahead_distance = 50.;
+    let boid_shape = Ball::new(1.);
+
+    query
+        .par_iter_mut()
+        .batching_strategy(BatchingStrategy::fixed(100))
+        .for_each(
+            |(sim_position, mut avoidance_force, velocity, max_speed, max_force, ())| {
+                let position = Vec2::new(sim_position.0.x, sim_position.0.y);
+                let boid_iso = Isometry::translation(position.x, position.y);
+
+                let mut sum = Vec3::new(0., 0., 0.);
+                let mut count = 0;
+
+                for obstacle in &obstacles.items {
+                    let obstacle_shape = Ball::new(obstacle.radius);
+                    let obstacle_iso = Isometry::translation(obstacle.center.x, obstacle.center.y);
+
+                    match query::closest_points(
+                        &boid_iso,
+                        &boid_shape,
+                        &obstacle_iso,
+                        &obstacle_shape,
+                        lookahead_distance,
+                    ) {
+                        Ok(ClosestPoints::WithinMargin(closest_on_boid, closest_on_obstacle)) => {
+                            let distance =
+                                (closest_on_boid - closest_on_obstacle).norm().max(0.01);
+                            let surface_normal = Vec2::new(
+                                closest_on_boid.x - obstacle.center.x,
+                                closest_on_boid.y - obstacle.center.y,
+                            )
+                            .normalize_or_zero();
+
+                            sum += Vec3::new(surface_normal.x, surface_normal.y, 0.) / distance;
+                            count += 1;
+                        }
+                        Ok(ClosestPoints::Intersecting) => {
+                            // The boid is already inside the obstacle, so there is no
+                            // surface point to steer towards; push it straight away
+                            // from the obstacle's center at full force instead.
+                            let surface_normal =
+                                (position - obstacle.center).normalize_or_zero();
+
+                            sum += Vec3::new(surface_normal.x, surface_normal.y, 0.) / 0.01;
+                            count += 1;
+                        }
+                        _ => continue,
+                    }
+                }
+
+                if count > 0 {
+                    sum = sum.normalize_or_zero() * max_speed.0;
+                    let mut steer = sum - velocity.0;
+
+                    steer = clamp_magnitude(steer, max_force.0);
+                    avoidance_force.0 += steer * force_multipliers.avoidance;
+                }
+            },
+        );
+}
+
+/// Per Nature of Code ch. 6, flee is just seek with the desired velocity
+/// negated: steer directly away from the predator at `MaxSpeed`.
+fn flee(
+    mut query: Query<(
+        &SimPosition,
+        &mut FleeForce,
+        &Velocity,
+        &MaxSpeed,
+        &MaxForce,
+        With<TrackedByKdTree>,
+    )>,
+    predators: Res<Predators>,
+    force_multipliers: Res<ForceMultipliers>,
+    flocking_params: Res<FlockingParams>,
+) {
+    let flee_radius = flocking_params.flee_radius;
+
+    query
+        .par_iter_mut()
+        .batching_strategy(BatchingStrategy::fixed(100))
+        .for_each(
+            |(sim_position, mut flee_force, velocity, max_speed, max_force, ())| {
+                let location = sim_position.0;
+
+                for predator_position in &predators.items {
+                    let away = location - *predator_position;
+                    let distance = away.length();
+
+                    if distance == 0.0 || distance > flee_radius {
+                        continue;
+                    }
+
+                    let desired_velocity = away.normalize_or_zero() * max_speed.0;
+                    let mut steer = desired_velocity - velocity.0;
+                    steer = clamp_magnitude(steer, max_force.0);
+
+                    flee_force.0 += steer * force_multipliers.flee;
+                }
+            },
+        );
+}
+
+pub(crate) fn apply_acceleration(
+    mut query: Query<(
+        &mut Velocity,
+        &mut Acceleration,
+        &mut AligmentForce,
+        &mut SeparationForce,
+        &mut CohesionForce,
+        &mut AvoidanceForce,
+        &mut FleeForce,
+        &MaxSpeed,
+    )>,
+    time: Res<Time>,
+) {
+    query.par_iter_mut().for_each(
+        |(
+            mut velocity,
+            mut acceleration,
+            mut alignment,
+            mut separation,
+            mut cohesion,
+            mut avoidance,
+            mut flee,
+            max_speed,
+        )| {
+            acceleration.0 += alignment.0;
+            acceleration.0 += separation.0;
+            acceleration.0 += cohesion.0;
+            acceleration.0 += avoidance.0;
+            acceleration.0 += flee.0;
+
+            velocity.0 += acceleration.0 * time.delta_seconds();
+            velocity.0 = clamp_magnitude(velocity.0, max_speed.0);
+
+            acceleration.0 *= 0.;
+            alignment.0 *= 0.;
+            separation.0 *= 0.;
+            cohesion.0 *= 0.;
+            avoidance.0 *= 0.;
+            flee.0 *= 0.;
+        },
+    );
+}
+
+fn update_position(
+    mut query: Query<(&mut SimPosition, &mut Transform, &Velocity)>,
+    time: Res<Time>,
+) {
+    query
+        .par_iter_mut()
+        .for_each(|(mut sim_position, mut transform, velocity)| {
+            sim_position.0 += velocity.0 * time.delta_seconds();
+            transform.rotation = Quat::from_rotation_z(velocity.0.y.atan2(velocity.0.x) + 180.);
+        });
+}
+
+fn update_position_3d(
+    mut query: Query<(&mut SimPosition, &mut Transform, &Velocity)>,
+    time: Res<Time>,
+) {
+    query
+        .par_iter_mut()
+        .for_each(|(mut sim_position, mut transform, velocity)| {
+            sim_position.0 += velocity.0 * time.delta_seconds();
+
+            if velocity.0.length_squared() > 0. {
+                transform.rotation = Quat::from_rotation_arc(Vec3::Z, velocity.0.normalize());
+            }
+        });
+}
+
+pub fn clamp_magnitude(value: Vec3, max: f32) -> Vec3 {
+    if value.length() > max {
+        value.normalize_or_zero() * max
+    } else {
+        value
+    }
+}
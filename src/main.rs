@@ -12,13 +12,16 @@ use bevy::{
     sprite::{MaterialMesh2dBundle, Mesh2dHandle},
     window::{PresentMode, WindowMode},
 };
+use bevy_inspector_egui::quick::WorldInspectorPlugin;
 
-use boids_plugin::{BoidBundle, BoidsPlugin};
-use flow_field_plugin::FlowFieldFollower;
+use boids_plugin::{BoidBundle, BoidsPlugin, FlockingParams};
+use flow_field_plugin::{FlowFieldFollower, FlowFieldPlugin};
+use presets::{load_preset_at_startup, PresetsPlugin};
 use rand::Rng;
 
 pub mod boids_plugin;
 pub mod flow_field_plugin;
+pub mod presets;
 
 fn main() {
     App::new()
@@ -38,16 +41,33 @@ fn main() {
         }))
         .add_plugins(LogDiagnosticsPlugin::default())
         .add_plugins(FrameTimeDiagnosticsPlugin)
-        .add_plugins(BoidsPlugin)
-        //.add_plugins(FlowFieldPlugin)
-        .add_systems(Startup, setup)
+        .add_plugins(BoidsPlugin::default())
+        // PresetsPlugin's load/save systems touch FlowFieldSettings, which
+        // only this plugin inserts, so it has to be added for those systems
+        // not to panic looking up a missing resource.
+        .add_plugins(FlowFieldPlugin)
+        .add_plugins(PresetsPlugin)
+        // Lets `FlockingParams`/`ForceMultipliers`/`FlowFieldSettings` be
+        // edited live at runtime, since they're already `Reflect` resources.
+        .add_plugins(WorldInspectorPlugin::new())
+        .add_systems(Startup, setup.after(load_preset_at_startup))
+        .add_systems(Update, spawn_boid_on_click)
         .run();
 }
 
+/// The mesh/material handles every spawned boid shares, stashed so
+/// `spawn_boid_on_click` can reuse them instead of allocating new assets.
+#[derive(Resource)]
+struct BoidVisuals {
+    mesh: Mesh2dHandle,
+    material: Handle<ColorMaterial>,
+}
+
 fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
+    flocking_params: Res<FlockingParams>,
 ) {
     commands.spawn(Camera2dBundle::default());
 
@@ -70,8 +90,54 @@ fn setup(
                 },
                 ..default()
             },
-            BoidBundle::default(),
+            BoidBundle::from_params(&flocking_params),
             FlowFieldFollower,
         ));
     }
+
+    commands.insert_resource(BoidVisuals {
+        mesh: mesh_handle,
+        material: material_handle,
+    });
+}
+
+/// Lets users stress and sculpt the flock interactively by spawning a boid
+/// at the clicked world position, registered in the spatial index the same
+/// way as every boid from `setup`.
+fn spawn_boid_on_click(
+    mut commands: Commands,
+    mouse_button: Res<Input<MouseButton>>,
+    windows: Query<&Window>,
+    camera_query: Query<(&Camera, &GlobalTransform)>,
+    visuals: Res<BoidVisuals>,
+    flocking_params: Res<FlockingParams>,
+) {
+    if !mouse_button.just_pressed(MouseButton::Left) {
+        return;
+    }
+
+    let Some(cursor_position) = windows.single().cursor_position() else {
+        return;
+    };
+
+    let (camera, camera_transform) = camera_query.single();
+    let Some(world_position) = camera.viewport_to_world_2d(camera_transform, cursor_position)
+    else {
+        return;
+    };
+
+    commands.spawn((
+        MaterialMesh2dBundle {
+            mesh: visuals.mesh.clone(),
+            material: visuals.material.clone(),
+            transform: Transform::from_translation(Vec3::new(
+                world_position.x,
+                world_position.y,
+                0.,
+            )),
+            ..default()
+        },
+        BoidBundle::from_params(&flocking_params),
+        FlowFieldFollower,
+    ));
 }
@@ -0,0 +1,74 @@
+use std::fs;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::boids_plugin::{FlockingParams, ForceMultipliers};
+use crate::flow_field_plugin::FlowFieldSettings;
+
+const PRESET_PATH: &str = "flocking_preset.ron";
+
+/// A full flocking "recipe": every live-tunable resource bundled together so
+/// it can be saved to disk as one unit and reloaded on startup.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct FlockingPreset {
+    pub flocking_params: FlockingParams,
+    pub force_multipliers: ForceMultipliers,
+    pub flow_field_settings: FlowFieldSettings,
+}
+
+/// Saves the current tuning to `flocking_preset.ron` on `F5` and reloads it
+/// from disk on startup, so a tuning session survives between runs.
+pub struct PresetsPlugin;
+
+impl Plugin for PresetsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, load_preset_at_startup)
+            .add_systems(Update, save_preset_on_input);
+    }
+}
+
+pub fn load_preset_at_startup(
+    mut flocking_params: ResMut<FlockingParams>,
+    mut force_multipliers: ResMut<ForceMultipliers>,
+    mut flow_field_settings: ResMut<FlowFieldSettings>,
+) {
+    let Ok(contents) = fs::read_to_string(PRESET_PATH) else {
+        return;
+    };
+
+    match ron::from_str::<FlockingPreset>(&contents) {
+        Ok(preset) => {
+            *flocking_params = preset.flocking_params;
+            *force_multipliers = preset.force_multipliers;
+            *flow_field_settings = preset.flow_field_settings;
+            info!("loaded flocking preset from {PRESET_PATH}");
+        }
+        Err(error) => warn!("failed to parse {PRESET_PATH}: {error}"),
+    }
+}
+
+fn save_preset_on_input(
+    keyboard: Res<Input<KeyCode>>,
+    flocking_params: Res<FlockingParams>,
+    force_multipliers: Res<ForceMultipliers>,
+    flow_field_settings: Res<FlowFieldSettings>,
+) {
+    if !keyboard.just_pressed(KeyCode::F5) {
+        return;
+    }
+
+    let preset = FlockingPreset {
+        flocking_params: *flocking_params,
+        force_multipliers: *force_multipliers,
+        flow_field_settings: *flow_field_settings,
+    };
+
+    match ron::ser::to_string_pretty(&preset, ron::ser::PrettyConfig::default()) {
+        Ok(serialized) => match fs::write(PRESET_PATH, serialized) {
+            Ok(()) => info!("saved flocking preset to {PRESET_PATH}"),
+            Err(error) => error!("failed to write {PRESET_PATH}: {error}"),
+        },
+        Err(error) => error!("failed to serialize flocking preset: {error}"),
+    }
+}